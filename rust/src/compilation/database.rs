@@ -17,15 +17,100 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+use std::cell::RefCell;
 use std::collections;
+use std::error;
+use std::fmt;
 use std::fs;
+use std::io;
 use std::path;
 
-use Result;
+use serde_json;
+
+
+/// The errors this module can produce.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// Reading from or writing to the backend failed, or canonicalizing an
+    /// entry's `directory` during normalization failed (then `path` is `None`).
+    Io { path: Option<path::PathBuf>, source: io::Error },
+    /// The backend's contents were not valid JSON, or `serde_json` failed
+    /// while writing entries out on save.
+    JsonParse(serde_json::Error),
+    /// The entry at `entry_index` could not be converted to an `Entry`.
+    UnexpectedSchema { entry_index: usize, cause: Box<DatabaseError> },
+    /// A `command` string could not be split into arguments because its
+    /// quotes do not balance.
+    UnbalancedQuotes { command: String },
+    /// A `directory`, `file` or `output` path is not valid UTF-8, so it
+    /// cannot be represented in the JSON format.
+    NonUtf8Path { path: path::PathBuf },
+    /// `DatabaseFormat::normalize` was set to `MissingSourcePolicy::Fail`
+    /// and `file` does not exist on disk.
+    MissingSource { file: path::PathBuf },
+    /// One or more entries failed independently; produced by `Database::load`
+    /// so callers can see every bad entry instead of only the first one.
+    Multiple(Vec<DatabaseError>),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Io { path: Some(path), source } =>
+                write!(f, "I/O error for {:?}: {}", path, source),
+            DatabaseError::Io { path: None, source } =>
+                write!(f, "I/O error: {}", source),
+            DatabaseError::JsonParse(cause) =>
+                write!(f, "Failed to parse JSON: {}", cause),
+            DatabaseError::UnexpectedSchema { entry_index, cause } =>
+                write!(f, "Entry {} does not match the expected schema: {}", entry_index, cause),
+            DatabaseError::UnbalancedQuotes { command } =>
+                write!(f, "Quotes are mismatched in command: {:?}", command),
+            DatabaseError::NonUtf8Path { path } =>
+                write!(f, "Path is not valid UTF-8: {:?}", path),
+            DatabaseError::MissingSource { file } =>
+                write!(f, "Source file does not exist: {:?}", file),
+            DatabaseError::Multiple(causes) => {
+                let messages = causes.iter()
+                    .map(DatabaseError::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{}", messages)
+            },
+        }
+    }
+}
+
+impl error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DatabaseError::Io { source, .. } => Some(source),
+            DatabaseError::JsonParse(cause) => Some(cause),
+            DatabaseError::UnexpectedSchema { cause, .. } => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DatabaseError {
+    fn from(source: io::Error) -> Self {
+        DatabaseError::Io { path: None, source }
+    }
+}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(cause: serde_json::Error) -> Self {
+        DatabaseError::JsonParse(cause)
+    }
+}
+
+/// This module's own `Result` alias, scoped to `DatabaseError` rather than
+/// the crate-wide error type.
+pub type Result<T> = ::std::result::Result<T, DatabaseError>;
 
 
 /// Represents a generic entry of the compilation database.
-#[derive(Hash, Debug)]
+#[derive(Clone, Debug)]
 pub struct Entry {
     pub directory: path::PathBuf,
     pub file: path::PathBuf,
@@ -44,51 +129,295 @@ impl PartialEq for Entry {
 impl Eq for Entry {
 }
 
+// `output` is deliberately left out of the hash, mirroring `PartialEq`
+// above -- otherwise two entries considered equal could land in different
+// buckets, which breaks `HashSet::take`/`contains` (used by `inner::merge`)
+// for entries that differ only in `output`.
+impl ::std::hash::Hash for Entry {
+    fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+        self.directory.hash(state);
+        self.file.hash(state);
+        self.command.hash(state);
+    }
+}
+
 type Entries = collections::HashSet<Entry>;
 
 
+/// Controls what `Database::save`/`save_streaming` do with an entry whose
+/// `file` no longer exists on disk once `DatabaseFormat::normalize` is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingSourcePolicy {
+    /// Drop the entry and keep going.
+    Skip,
+    /// Fail the whole save with `DatabaseError::MissingSource`.
+    Fail,
+}
+
 /// Represents the expected format of the JSON compilation database.
 pub struct DatabaseFormat {
     pub command_as_array: bool,
 
+    /// Consulted by `save`/`save_streaming` only, never by `load`. When
+    /// set, canonicalizes `directory` and resolves `file`/`output` against
+    /// it before writing; `None` passes paths through as given.
+    pub normalize: Option<MissingSourcePolicy>,
+
     // Other attributes might be:
     // - output field dropped or preserved.
 }
 
-/// Represents a JSON compilation database.
-pub struct Database {
+/// The serde-visible shape of a single compilation database record, kept
+/// separate from `Entry` so the `command`/`arguments` duality stays
+/// isolated to the I/O layer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GenericEntry {
+    StringEntry {
+        directory: String,
+        file: String,
+        command: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output: Option<String>,
+    },
+    ArrayEntry {
+        directory: String,
+        file: String,
+        arguments: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output: Option<String>,
+    },
+}
+
+pub type GenericEntries = Vec<GenericEntry>;
+
+/// Abstracts away where the raw (pre-`Entry`-conversion) records come from
+/// and go to, so the serde/shellwords conversion logic in `inner` doesn't
+/// need to know whether it's talking to a file, a socket or a test fixture.
+pub trait DatabaseBackend {
+    fn load(&self) -> Result<GenericEntries>;
+    fn save(&self, entries: &GenericEntries) -> Result<()>;
+
+    /// Like `load`, but converted straight to `Entry`s without necessarily
+    /// materializing the full `GenericEntries` in between. Defaults to
+    /// `load`; override where incremental reading is possible.
+    fn load_streaming(&self) -> Result<Entries> {
+        let generic_entries = self.load()?;
+        inner::convert_all(generic_entries.iter().enumerate())
+    }
+
+    /// Like `save`, but takes `Entry`s and converts them itself, without
+    /// necessarily materializing the full `GenericEntries` first. Defaults
+    /// to converting every entry and then calling `save`; override where
+    /// incremental writing is possible.
+    fn save_streaming(&self, entries: &Entries, format: &DatabaseFormat) -> Result<()> {
+        let generic_entries = entries.iter()
+            .map(|entry| inner::from(entry, format))
+            .collect::<Result<GenericEntries>>()?;
+        self.save(&generic_entries)
+    }
+}
+
+/// The historical behavior: a compilation database stored as a single JSON
+/// file on disk.
+pub struct JsonFileBackend {
     path: path::PathBuf,
 }
 
-impl Database {
+impl JsonFileBackend {
     pub fn new(path: &path::Path) -> Self {
-        Database { path: path.to_path_buf(), }
+        JsonFileBackend { path: path.to_path_buf() }
+    }
+}
+
+impl DatabaseBackend for JsonFileBackend {
+    fn load(&self) -> Result<GenericEntries> {
+        let io_err = |source| DatabaseError::Io { path: Some(self.path.clone()), source };
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .open(&self.path)
+            .map_err(io_err)?;
+        let entries: GenericEntries = serde_json::from_reader(file)?;
+        Ok(entries)
     }
 
-    pub fn load(&self) -> Result<Entries> {
-        let generic_entries = inner::load(&self.path)?;
-        let entries = generic_entries.iter()
-            .map(|entry| inner::into(entry))
-            .collect::<Result<Entries>>();
-        // In case of error, let's be verbose which entries were problematic.
-        if let Err(_) = entries {
-            let errors = generic_entries.iter()
-                .map(|entry| inner::into(entry))
-                .filter_map(Result::err)
-                .map(|error| error.to_string())
-                .collect::<Vec<_>>()
-                .join(", ");
-            Err(errors.into())
+    fn save(&self, entries: &GenericEntries) -> Result<()> {
+        let io_err = |source| DatabaseError::Io { path: Some(self.path.clone()), source };
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&self.path)
+            .map_err(io_err)?;
+        serde_json::ser::to_writer_pretty(file, entries)
+            .map_err(|error| error.into())
+    }
+
+    /// Reads the `[...]` array one element at a time via a streaming
+    /// `serde::de::Visitor` instead of buffering the whole file first.
+    fn load_streaming(&self) -> Result<Entries> {
+        struct EntrySeqVisitor<'a> {
+            entries: &'a mut Entries,
+            errors: &'a mut Vec<DatabaseError>,
+        }
+
+        impl<'de, 'a> ::serde::de::Visitor<'de> for EntrySeqVisitor<'a> {
+            type Value = ();
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a JSON array of compilation database entries")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> ::std::result::Result<Self::Value, A::Error>
+                where A: ::serde::de::SeqAccess<'de>
+            {
+                let mut entry_index = 0;
+                while let Some(record) = seq.next_element::<GenericEntry>()? {
+                    match inner::into(&record) {
+                        Ok(entry) => { self.entries.insert(entry); },
+                        Err(cause) => self.errors.push(DatabaseError::UnexpectedSchema {
+                            entry_index,
+                            cause: Box::new(cause),
+                        }),
+                    }
+                    entry_index += 1;
+                }
+                Ok(())
+            }
+        }
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .open(&self.path)
+            .map_err(|source| DatabaseError::Io { path: Some(self.path.clone()), source })?;
+
+        let mut entries = Entries::new();
+        let mut errors = Vec::new();
+        let mut deserializer = serde_json::Deserializer::from_reader(file);
+        ::serde::Deserializer::deserialize_seq(
+            &mut deserializer,
+            EntrySeqVisitor { entries: &mut entries, errors: &mut errors },
+        )?;
+
+        if errors.is_empty() {
+            Ok(entries)
         } else {
-            entries
+            Err(DatabaseError::Multiple(errors))
         }
     }
 
+    /// Writes the `[`, each entry as it is converted, then the `]`,
+    /// without ever holding the full `GenericEntries` in memory at once.
+    fn save_streaming(&self, entries: &Entries, format: &DatabaseFormat) -> Result<()> {
+        use std::io::Write;
+
+        let io_err = |source| DatabaseError::Io { path: Some(self.path.clone()), source };
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&self.path)
+            .map_err(io_err)?;
+
+        file.write_all(b"[").map_err(io_err)?;
+        for (index, entry) in entries.iter().enumerate() {
+            if index > 0 {
+                file.write_all(b",").map_err(io_err)?;
+            }
+            let generic_entry = inner::from(entry, format)?;
+            serde_json::to_writer(&mut file, &generic_entry)?;
+        }
+        file.write_all(b"]").map_err(io_err)?;
+        Ok(())
+    }
+}
+
+/// An in-memory backend, useful for feeding a `Database` from tests without
+/// touching the filesystem.
+pub struct InMemoryBackend {
+    entries: RefCell<GenericEntries>,
+}
+
+impl InMemoryBackend {
+    pub fn new(entries: GenericEntries) -> Self {
+        InMemoryBackend { entries: RefCell::new(entries) }
+    }
+}
+
+impl DatabaseBackend for InMemoryBackend {
+    fn load(&self) -> Result<GenericEntries> {
+        Ok(self.entries.borrow().clone())
+    }
+
+    fn save(&self, entries: &GenericEntries) -> Result<()> {
+        *self.entries.borrow_mut() = entries.clone();
+        Ok(())
+    }
+}
+
+/// Represents a JSON compilation database.
+pub struct Database {
+    backend: Box<dyn DatabaseBackend>,
+}
+
+impl Database {
+    pub fn new(backend: Box<dyn DatabaseBackend>) -> Self {
+        Database { backend }
+    }
+
+    pub fn load(&self) -> Result<Entries> {
+        let generic_entries = self.backend.load()?;
+        inner::convert_all(generic_entries.iter().enumerate())
+    }
+
     pub fn save(&self, entries: &Entries, format: &DatabaseFormat) -> Result<()> {
+        let normalized;
+        let entries = match format.normalize {
+            Some(policy) => {
+                normalized = inner::normalize(entries, policy)?;
+                &normalized
+            },
+            None => entries,
+        };
         let generic_entries = entries.iter()
             .map(|entry| inner::from(entry, format))
-            .collect::<Result<Vec<_>>>()?;
-        inner::save(&self.path, &generic_entries)
+            .collect::<Result<GenericEntries>>()?;
+        self.backend.save(&generic_entries)
+    }
+
+    /// Like `save`, but first loads whatever is already at the backend and
+    /// unions it with `entries` instead of overwriting it. A target that
+    /// does not exist yet is treated as empty rather than as an error.
+    pub fn merge_save(&self, entries: &Entries, format: &DatabaseFormat) -> Result<()> {
+        let existing = match self.load() {
+            Ok(existing) => existing,
+            Err(DatabaseError::Io { ref source, .. }) if source.kind() == io::ErrorKind::NotFound =>
+                Entries::new(),
+            Err(other) => return Err(other),
+        };
+        let merged = inner::merge(existing, entries);
+        self.save(&merged, format)
+    }
+
+    /// Streaming counterpart of `load`. See `DatabaseBackend::load_streaming`.
+    pub fn load_streaming(&self) -> Result<Entries> {
+        self.backend.load_streaming()
+    }
+
+    /// Streaming counterpart of `save`. See `DatabaseBackend::save_streaming`.
+    pub fn save_streaming(&self, entries: &Entries, format: &DatabaseFormat) -> Result<()> {
+        let normalized;
+        let entries = match format.normalize {
+            Some(policy) => {
+                normalized = inner::normalize(entries, policy)?;
+                &normalized
+            },
+            None => entries,
+        };
+        self.backend.save_streaming(entries, format)
     }
 }
 
@@ -104,10 +433,21 @@ mod test {
     #[test]
     #[should_panic]
     fn test_load_not_existing_file_fails() {
-        let sut = Database::new(path::Path::new("/not/exists/file.json"));
+        let backend = JsonFileBackend::new(path::Path::new("/not/exists/file.json"));
+        let sut = Database::new(Box::new(backend));
         let _ = sut.load().unwrap();
     }
 
+    #[test]
+    fn test_load_not_existing_file_reports_path() {
+        let missing = path::Path::new("/not/exists/file.json");
+        let sut = Database::new(Box::new(JsonFileBackend::new(missing)));
+        match sut.load() {
+            Err(DatabaseError::Io { path: Some(path), .. }) => assert_eq!(missing, path),
+            other => panic!("expected Err(Io {{ path: Some(..), .. }}), got {:?}", other),
+        }
+    }
+
     #[test]
     #[should_panic]
     fn test_load_json_failed() {
@@ -116,7 +456,7 @@ mod test {
         comp_db_file.write(br#"this is not json"#)
             .expect("test file content write failed");
 
-        let sut = Database::new(comp_db_file.path());
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
         let _ = sut.load().unwrap();
     }
 
@@ -128,7 +468,7 @@ mod test {
         comp_db_file.write(br#"{ "file": "string" }"#)
             .expect("test file content write failed");
 
-        let sut = Database::new(comp_db_file.path());
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
         let _ = sut.load().unwrap();
     }
 
@@ -137,7 +477,7 @@ mod test {
         let comp_db_file = TestFile::new()?;
         comp_db_file.write(br#"[]"#)?;
 
-        let sut = Database::new(comp_db_file.path());
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
         let entries = sut.load()?;
 
         let expected = Entries::new();
@@ -164,7 +504,7 @@ mod test {
             ]"#
         )?;
 
-        let sut = Database::new(comp_db_file.path());
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
         let entries = sut.load()?;
 
         let expected = expected_values();
@@ -191,7 +531,7 @@ mod test {
             ]"#
         )?;
 
-        let sut = Database::new(comp_db_file.path());
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
         let entries = sut.load()?;
 
         let expected = expected_values();
@@ -213,16 +553,50 @@ mod test {
             ]"#)
             .expect("test file content write failed");
 
-        let sut = Database::new(comp_db_file.path());
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
         let _ = sut.load().unwrap();
     }
 
+    #[test]
+    fn test_load_reports_index_of_bad_entry() {
+        let comp_db_file = TestFile::new()
+            .expect("test file setup failed");
+        comp_db_file.write(br#"[
+                {
+                    "directory": "/home/user",
+                    "file": "./file_a.c",
+                    "command": "cc -c ./file_a.c -o ./file_a.o"
+                },
+                {
+                    "directory": "/home/user",
+                    "file": "./file_b.c",
+                    "command": "cc -Dvalue=\"this"
+                }
+            ]"#)
+            .expect("test file content write failed");
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        match sut.load() {
+            Err(DatabaseError::Multiple(errors)) => {
+                assert_eq!(1, errors.len());
+                match &errors[0] {
+                    DatabaseError::UnexpectedSchema { entry_index, cause } => {
+                        assert_eq!(1, *entry_index);
+                        assert!(matches!(**cause, DatabaseError::UnbalancedQuotes { .. }));
+                    },
+                    other => panic!("unexpected error variant: {:?}", other),
+                }
+            },
+            other => panic!("expected Err(Multiple(..)), got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_save_string_command() -> Result<()> {
         let comp_db_file = TestFile::new()?;
 
-        let sut = Database::new(comp_db_file.path());
-        let formatter = DatabaseFormat { command_as_array: false };
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat { command_as_array: false, normalize: None };
 
         let expected = expected_values();
         sut.save(&expected, &formatter)?;
@@ -242,8 +616,8 @@ mod test {
     fn test_save_array_command() -> Result<()> {
         let comp_db_file = TestFile::new()?;
 
-        let sut = Database::new(comp_db_file.path());
-        let formatter = DatabaseFormat { command_as_array: true };
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
 
         let expected = expected_values();
         sut.save(&expected, &formatter)?;
@@ -259,6 +633,354 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_save_normalize_resolves_relative_paths() -> Result<()> {
+        let comp_db_file = TestFile::new()?;
+        let source_dir = comp_db_file.directory.path();
+        fs::write(source_dir.join("main.c"), "int main() {}")?;
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat {
+            command_as_array: true,
+            normalize: Some(MissingSourcePolicy::Skip),
+        };
+
+        let mut entries = Entries::new();
+        entries.insert(Entry {
+            directory: source_dir.to_path_buf(),
+            file: path::PathBuf::from("main.c"),
+            command: vec_of_strings!("cc", "-c", "main.c", "-o", "main.o"),
+            output: Some(path::PathBuf::from("main.o")),
+        });
+        sut.save(&entries, &formatter)?;
+
+        let loaded = sut.load()?;
+        assert_eq!(1, loaded.len());
+        let entry = loaded.iter().next().unwrap();
+        let canonical_dir = source_dir.canonicalize()?;
+        assert_eq!(canonical_dir, entry.directory);
+        assert_eq!(canonical_dir.join("main.c"), entry.file);
+        assert_eq!(Some(canonical_dir.join("main.o")), entry.output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_normalize_skips_missing_source_under_lenient_policy() -> Result<()> {
+        let comp_db_file = TestFile::new()?;
+        let source_dir = comp_db_file.directory.path().to_path_buf();
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat {
+            command_as_array: true,
+            normalize: Some(MissingSourcePolicy::Skip),
+        };
+
+        let mut entries = Entries::new();
+        entries.insert(Entry {
+            directory: source_dir,
+            file: path::PathBuf::from("missing.c"),
+            command: vec_of_strings!("cc", "-c", "missing.c"),
+            output: None,
+        });
+        sut.save(&entries, &formatter)?;
+
+        let loaded = sut.load()?;
+        assert_eq!(0, loaded.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_normalize_merges_entries_that_collide_after_resolving_paths() -> Result<()> {
+        let comp_db_file = TestFile::new()?;
+        let source_dir = comp_db_file.directory.path();
+        fs::write(source_dir.join("main.c"), "int main() {}")?;
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat {
+            command_as_array: true,
+            normalize: Some(MissingSourcePolicy::Skip),
+        };
+
+        // "main.c" and "./main.c" resolve to the same file.
+        let mut entries = Entries::new();
+        entries.insert(Entry {
+            directory: source_dir.to_path_buf(),
+            file: path::PathBuf::from("main.c"),
+            command: vec_of_strings!("cc", "-c", "main.c", "-o", "main.o"),
+            output: Some(path::PathBuf::from("main.o")),
+        });
+        entries.insert(Entry {
+            directory: source_dir.to_path_buf(),
+            file: path::PathBuf::from("./main.c"),
+            command: vec_of_strings!("cc", "-c", "main.c", "-o", "main.o"),
+            output: None,
+        });
+        sut.save(&entries, &formatter)?;
+
+        let loaded = sut.load()?;
+        assert_eq!(1, loaded.len());
+        let entry = loaded.iter().next().unwrap();
+        let canonical_dir = source_dir.canonicalize()?;
+        assert_eq!(Some(canonical_dir.join("main.o")), entry.output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_normalize_fails_on_missing_source_under_strict_policy() {
+        let comp_db_file = TestFile::new()
+            .expect("test file setup failed");
+        let source_dir = comp_db_file.directory.path().to_path_buf();
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat {
+            command_as_array: true,
+            normalize: Some(MissingSourcePolicy::Fail),
+        };
+
+        let mut entries = Entries::new();
+        entries.insert(Entry {
+            directory: source_dir,
+            file: path::PathBuf::from("missing.c"),
+            command: vec_of_strings!("cc", "-c", "missing.c"),
+            output: None,
+        });
+
+        match sut.save(&entries, &formatter) {
+            Err(DatabaseError::MissingSource { .. }) => {},
+            other => panic!("expected Err(MissingSource), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_merge_save_new_output_wins_over_old() -> Result<()> {
+        let sut = Database::new(Box::new(InMemoryBackend::new(GenericEntries::new())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
+
+        let mut first_run = Entries::new();
+        first_run.insert(Entry {
+            directory: path::PathBuf::from("/home/user"),
+            file: path::PathBuf::from("./file_a.c"),
+            command: vec_of_strings!("cc", "-c", "./file_a.c", "-o", "./file_a.o"),
+            output: Some(path::PathBuf::from("./file_a.o")),
+        });
+        sut.save(&first_run, &formatter)?;
+
+        let mut second_run = Entries::new();
+        second_run.insert(Entry {
+            directory: path::PathBuf::from("/home/user"),
+            file: path::PathBuf::from("./file_a.c"),
+            command: vec_of_strings!("cc", "-c", "./file_a.c", "-o", "./file_a.o"),
+            output: Some(path::PathBuf::from("./file_a_v2.o")),
+        });
+        sut.merge_save(&second_run, &formatter)?;
+
+        let entries = sut.load()?;
+        assert_eq!(1, entries.len());
+        let merged = entries.iter().next().unwrap();
+        assert_eq!(Some(path::PathBuf::from("./file_a_v2.o")), merged.output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_save_keeps_old_output_when_new_has_none() -> Result<()> {
+        let sut = Database::new(Box::new(InMemoryBackend::new(GenericEntries::new())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
+
+        let mut first_run = Entries::new();
+        first_run.insert(Entry {
+            directory: path::PathBuf::from("/home/user"),
+            file: path::PathBuf::from("./file_a.c"),
+            command: vec_of_strings!("cc", "-c", "./file_a.c", "-o", "./file_a.o"),
+            output: Some(path::PathBuf::from("./file_a.o")),
+        });
+        sut.save(&first_run, &formatter)?;
+
+        let mut second_run = Entries::new();
+        second_run.insert(Entry {
+            directory: path::PathBuf::from("/home/user"),
+            file: path::PathBuf::from("./file_a.c"),
+            command: vec_of_strings!("cc", "-c", "./file_a.c", "-o", "./file_a.o"),
+            output: None,
+        });
+        sut.merge_save(&second_run, &formatter)?;
+
+        let entries = sut.load()?;
+        assert_eq!(1, entries.len());
+        let merged = entries.iter().next().unwrap();
+        assert_eq!(Some(path::PathBuf::from("./file_a.o")), merged.output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_save_accumulates_distinct_entries() -> Result<()> {
+        let sut = Database::new(Box::new(InMemoryBackend::new(GenericEntries::new())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
+
+        let mut first_run = Entries::new();
+        first_run.insert(Entry {
+            directory: path::PathBuf::from("/home/user"),
+            file: path::PathBuf::from("./file_a.c"),
+            command: vec_of_strings!("cc", "-c", "./file_a.c", "-o", "./file_a.o"),
+            output: Some(path::PathBuf::from("./file_a.o")),
+        });
+        sut.save(&first_run, &formatter)?;
+
+        let mut second_run = Entries::new();
+        second_run.insert(Entry {
+            directory: path::PathBuf::from("/home/user"),
+            file: path::PathBuf::from("./file_b.c"),
+            command: vec_of_strings!("cc", "-c", "./file_b.c", "-o", "./file_b.o"),
+            output: Some(path::PathBuf::from("./file_b.o")),
+        });
+        sut.merge_save(&second_run, &formatter)?;
+
+        let entries = sut.load()?;
+        assert_eq!(2, entries.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_save_treats_missing_target_as_empty() -> Result<()> {
+        let comp_db_file = TestFile::new()?;
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
+
+        let mut entries = Entries::new();
+        entries.insert(Entry {
+            directory: path::PathBuf::from("/home/user"),
+            file: path::PathBuf::from("./file_a.c"),
+            command: vec_of_strings!("cc", "-c", "./file_a.c", "-o", "./file_a.o"),
+            output: Some(path::PathBuf::from("./file_a.o")),
+        });
+        sut.merge_save(&entries, &formatter)?;
+
+        let loaded = sut.load()?;
+        assert_eq!(1, loaded.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_save_propagates_load_errors_other_than_missing_target() {
+        let comp_db_file = TestFile::new()
+            .expect("test file setup failed");
+        comp_db_file.write(br#"[
+                {
+                    "directory": "/home/user",
+                    "file": "./file_a.c",
+                    "command": "cc -c ./file_a.c -o ./file_a.o"
+                },
+                {
+                    "directory": "/home/user",
+                    "file": "./file_b.c",
+                    "command": "cc -Dvalue=\"this"
+                }
+            ]"#)
+            .expect("test file content write failed");
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
+
+        let mut entries = Entries::new();
+        entries.insert(Entry {
+            directory: path::PathBuf::from("/home/user"),
+            file: path::PathBuf::from("./file_c.c"),
+            command: vec_of_strings!("cc", "-c", "./file_c.c", "-o", "./file_c.o"),
+            output: None,
+        });
+
+        // The valid pre-existing entry must not be silently discarded.
+        match sut.merge_save(&entries, &formatter) {
+            Err(DatabaseError::Multiple(_)) => {},
+            other => panic!("expected Err(Multiple(..)), got {:?}", other),
+        }
+
+        let content = comp_db_file.read()
+            .expect("test file re-read failed");
+        assert!(content.contains("file_a.c"));
+        assert!(!content.contains("file_c.c"));
+    }
+
+    #[test]
+    fn test_in_memory_backend_round_trip() -> Result<()> {
+        let sut = Database::new(Box::new(InMemoryBackend::new(GenericEntries::new())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
+
+        let expected = expected_values();
+        sut.save(&expected, &formatter)?;
+
+        let entries = sut.load()?;
+
+        assert_eq!(expected, entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_streaming_round_trip() -> Result<()> {
+        let comp_db_file = TestFile::new()?;
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
+
+        let expected = expected_values();
+        sut.save_streaming(&expected, &formatter)?;
+
+        let entries = sut.load_streaming()?;
+
+        let expected = expected_values();
+        assert_eq!(expected, entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_streaming_produces_a_plain_json_array() -> Result<()> {
+        let comp_db_file = TestFile::new()?;
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        let formatter = DatabaseFormat { command_as_array: true, normalize: None };
+
+        let expected = expected_values();
+        sut.save_streaming(&expected, &formatter)?;
+
+        // Read back with the plain (non-streaming) loader.
+        let entries = sut.load()?;
+        assert_eq!(expected, entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_streaming_reports_index_of_bad_entry() {
+        let comp_db_file = TestFile::new()
+            .expect("test file setup failed");
+        comp_db_file.write(br#"[
+                {
+                    "directory": "/home/user",
+                    "file": "./file_a.c",
+                    "command": "cc -c ./file_a.c -o ./file_a.o"
+                },
+                {
+                    "directory": "/home/user",
+                    "file": "./file_b.c",
+                    "command": "cc -Dvalue=\"this"
+                }
+            ]"#)
+            .expect("test file content write failed");
+
+        let sut = Database::new(Box::new(JsonFileBackend::new(comp_db_file.path())));
+        match sut.load_streaming() {
+            Err(DatabaseError::Multiple(errors)) => {
+                assert_eq!(1, errors.len());
+                match &errors[0] {
+                    DatabaseError::UnexpectedSchema { entry_index, cause } => {
+                        assert_eq!(1, *entry_index);
+                        assert!(matches!(**cause, DatabaseError::UnbalancedQuotes { .. }));
+                    },
+                    other => panic!("unexpected error variant: {:?}", other),
+                }
+            },
+            other => panic!("expected Err(Multiple(..)), got {:?}", other),
+        }
+    }
+
     #[allow(dead_code)]
     struct TestFile {
         directory: tempfile::TempDir,
@@ -330,54 +1052,99 @@ mod test {
 
 mod inner {
     use super::*;
-    use serde_json;
     use shellwords;
 
-    #[derive(Debug, Serialize, Deserialize)]
-    #[serde(untagged)]
-    pub enum GenericEntry {
-        StringEntry {
-            directory: String,
-            file: String,
-            command: String,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            output: Option<String>,
-        },
-        ArrayEntry {
-            directory: String,
-            file: String,
-            arguments: Vec<String>,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            output: Option<String>,
-        },
-    }
-
-    type GenericEntries = Vec<GenericEntry>;
-
-
-    pub fn load(path: &path::Path) -> Result<GenericEntries> {
-        let file = fs::OpenOptions::new()
-            .read(true)
-            .open(path)?;
-        let entries: GenericEntries = serde_json::from_reader(file)?;
-        Ok(entries)
+    /// Converts a sequence of indexed `GenericEntry`s to `Entry`s, collecting
+    /// every conversion failure (tagged with its original index) instead of
+    /// stopping at the first one.
+    pub fn convert_all<'a, I>(entries: I) -> Result<Entries>
+        where I: Iterator<Item = (usize, &'a GenericEntry)>
+    {
+        let mut converted = Entries::new();
+        let mut errors = Vec::new();
+        for (entry_index, entry) in entries {
+            match into(entry) {
+                Ok(entry) => { converted.insert(entry); },
+                Err(cause) => errors.push(DatabaseError::UnexpectedSchema {
+                    entry_index,
+                    cause: Box::new(cause),
+                }),
+            }
+        }
+        if errors.is_empty() {
+            Ok(converted)
+        } else {
+            Err(DatabaseError::Multiple(errors))
+        }
     }
 
-    pub fn save(path: &path::Path, entries: &GenericEntries) -> Result<()> {
-        let file = fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(path)?;
-        serde_json::ser::to_writer_pretty(file, entries)
-            .map_err(|error| error.into())
+    /// Unions `existing` with `incoming`, keyed on `directory`/`file`/
+    /// `command`. On a collision, `incoming`'s `output` wins unless it's
+    /// `None`, in which case `existing`'s is kept.
+    pub fn merge(existing: Entries, incoming: &Entries) -> Entries {
+        let mut merged = existing;
+        for entry in incoming {
+            merge_one(&mut merged, entry);
+        }
+        merged
+    }
+
+    /// Inserts `entry` into `into`, applying `merge`'s collision precedence
+    /// if an entry with the same key is already present.
+    fn merge_one(into: &mut Entries, entry: &Entry) {
+        let output = match into.take(entry) {
+            Some(ref old) if entry.output.is_none() => old.output.clone(),
+            _ => entry.output.clone(),
+        };
+        into.insert(Entry {
+            directory: entry.directory.clone(),
+            file: entry.file.clone(),
+            command: entry.command.clone(),
+            output,
+        });
+    }
+
+    /// Canonicalizes each entry's `directory` and resolves its `file`/
+    /// `output` against it, applying `policy` to entries whose `file` no
+    /// longer exists. Entries that collide after normalization are merged.
+    pub fn normalize(entries: &Entries, policy: MissingSourcePolicy) -> Result<Entries> {
+        let mut normalized = Entries::new();
+        for entry in entries {
+            let directory = fs::canonicalize(&entry.directory)?;
+            let file = resolve_against(&directory, &entry.file);
+            let output = entry.output.as_ref().map(|path| resolve_against(&directory, path));
+
+            if !file.exists() {
+                match policy {
+                    MissingSourcePolicy::Skip => continue,
+                    MissingSourcePolicy::Fail =>
+                        return Err(DatabaseError::MissingSource { file }),
+                }
+            }
+
+            merge_one(&mut normalized, &Entry {
+                directory,
+                file,
+                command: entry.command.clone(),
+                output,
+            });
+        }
+        Ok(normalized)
+    }
+
+    fn resolve_against(directory: &path::Path, file: &path::Path) -> path::PathBuf {
+        if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            directory.join(file)
+        }
     }
 
     pub fn from(entry: &Entry, format: &DatabaseFormat) -> Result<GenericEntry> {
         fn path_to_string(path: &path::Path) -> Result<String> {
             match path.to_str() {
                 Some(str) => Ok(str.to_string()),
-                None => Err(format!("Failed to convert to string {:?}", path).into()),
+                None => Err(DatabaseError::NonUtf8Path { path: path.to_path_buf() }),
             }
         }
 
@@ -436,7 +1203,7 @@ mod inner {
                         })
                     },
                     Err(_) =>
-                        Err(format!("Quotes are mismatch in {:?}", command).into()),
+                        Err(DatabaseError::UnbalancedQuotes { command: command.clone() }),
                 }
             }
         }
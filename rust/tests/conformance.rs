@@ -0,0 +1,161 @@
+/*  Copyright (C) 2012-2018 by László Nagy
+    This file is part of Bear.
+
+    Bear is a tool to generate compilation database for clang tooling.
+
+    Bear is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Bear is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Data-driven conformance suite for `compilation::database`.
+//!
+//! Walks `tests/corpus/` for `<name>.in.json` / `<name>.expected.json`
+//! pairs and, for each one found, registers a test (via `libtest_mimic`,
+//! the approach `dhall_rust` uses for its spec suite) that checks:
+//!
+//! - loading the (possibly messy) `.in.json` produces the same `Entry` set
+//!   as loading the canonical `.expected.json`, regardless of surface
+//!   differences like `command` vs. `arguments` or quoting;
+//! - saving that set back out and reloading it round-trips losslessly,
+//!   under both `command_as_array` settings.
+//!
+//! New regression cases -- a mix of `command`/`arguments`, a missing
+//! `output`, odd shell escaping -- can be added by dropping a pair of
+//! files into `tests/corpus/`, without writing any Rust.
+
+extern crate bear;
+extern crate libtest_mimic;
+extern crate tempfile;
+extern crate walkdir;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use bear::compilation::database::{Database, DatabaseFormat, Entry, JsonFileBackend};
+use libtest_mimic::{Arguments, Failed, Trial};
+
+fn main() {
+    let args = Arguments::from_args();
+
+    let corpus_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let tests = discover_corpus(&corpus_root)
+        .into_iter()
+        .map(|pair| {
+            let name = pair.name.clone();
+            Trial::test(name, move || check_pair(&pair))
+        })
+        .collect();
+
+    libtest_mimic::run(&args, tests).exit();
+}
+
+/// A discovered `<name>.in.json` / `<name>.expected.json` pair.
+struct CorpusPair {
+    name: String,
+    input: PathBuf,
+    expected: PathBuf,
+}
+
+/// Recursively finds every `.in.json` file under `root` that has a sibling
+/// `.expected.json`, so fixtures can be grouped into subdirectories however
+/// contributors find clearest.
+fn discover_corpus(root: &Path) -> Vec<CorpusPair> {
+    let mut pairs = walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let input = entry.into_path();
+            let stem = input.file_name()?
+                .to_str()?
+                .strip_suffix(".in.json")?
+                .to_string();
+            let expected = input.with_file_name(format!("{}.expected.json", stem));
+            if expected.is_file() {
+                Some(CorpusPair { name: stem, input, expected })
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    pairs.sort_by(|a, b| a.name.cmp(&b.name));
+    pairs
+}
+
+fn check_pair(pair: &CorpusPair) -> Result<(), Failed> {
+    let actual = load(&pair.input)?;
+    let expected = load(&pair.expected)?;
+    assert_same("`.in.json` does not match `.expected.json` once loaded", &actual, &expected)?;
+
+    for &command_as_array in &[false, true] {
+        let format = DatabaseFormat { command_as_array, normalize: None };
+        let round_tripped = round_trip(&actual, &format)?;
+        assert_same(
+            &format!("round-trip through command_as_array={} lost information", command_as_array),
+            &round_tripped,
+            &actual,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<HashSet<Entry>, Failed> {
+    let backend = JsonFileBackend::new(path);
+    Database::new(Box::new(backend)).load()
+        .map_err(|error| Failed::from(format!("failed to load {}: {}", path.display(), error)))
+}
+
+fn round_trip(entries: &HashSet<Entry>, format: &DatabaseFormat) -> Result<HashSet<Entry>, Failed> {
+    let scratch = tempfile::Builder::new()
+        .prefix("bear-conformance-")
+        .suffix(".json")
+        .tempfile()
+        .map_err(|error| Failed::from(format!("failed to create scratch file: {}", error)))?;
+
+    let sut = Database::new(Box::new(JsonFileBackend::new(scratch.path())));
+    sut.save(entries, format)
+        .map_err(|error| Failed::from(format!("failed to save: {}", error)))?;
+    sut.load()
+        .map_err(|error| Failed::from(format!("failed to reload: {}", error)))
+}
+
+/// Like `assert_eq!`, but renders both sides as sorted, line-per-entry text
+/// instead of one opaque `HashSet` debug dump, since the sets being compared
+/// here are unordered and can get large.
+///
+/// Compares `rows(..)` rather than the sets directly: `Entry::PartialEq`
+/// deliberately ignores `output` (see `database.rs`), which would let a
+/// regression that drops or corrupts `output` pass this suite silently.
+fn assert_same(context: &str, actual: &HashSet<Entry>, expected: &HashSet<Entry>) -> Result<(), Failed> {
+    let actual_rows = rows(actual);
+    let expected_rows = rows(expected);
+    if actual_rows == expected_rows {
+        return Ok(());
+    }
+    Err(Failed::from(format!(
+        "{}\n--- actual ---\n{}\n--- expected ---\n{}",
+        context,
+        actual_rows.join("\n"),
+        expected_rows.join("\n"),
+    )))
+}
+
+/// Renders every entry, `output` included, as a sorted, line-per-entry
+/// `Vec<String>` suitable for a straightforward `==`/diff comparison.
+fn rows(entries: &HashSet<Entry>) -> Vec<String> {
+    let mut lines = entries.iter()
+        .map(|entry| format!("{:?} {:?} {:?} {:?}", entry.directory, entry.file, entry.command, entry.output))
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines
+}